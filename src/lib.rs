@@ -0,0 +1,529 @@
+//! Channel packing for up to four source images via a swizzle mask (which source feeds each
+//! output channel) and a select mask (which channel of that source is read).
+//!
+//! The swizzle mask maps the character at each output channel position to either a source index
+//! (`0`-`3`) or a fill value (`b` = 0, `w` = max, `g` = mid-gray). The select mask is indexed by
+//! source: its character at position `i` picks the channel (`r`, `g`, `b`, `a`) read from source `i`.
+
+use std::thread;
+use image::{DynamicImage, ImageBuffer};
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum ChannelFormat {
+    Uint8,
+    Uint16,
+    Float32
+}
+
+/// The bit depth (and representation) of the combined output image.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum OutputDepth {
+    Uint8,
+    Uint16,
+    Float32
+}
+
+impl OutputDepth {
+    fn from_format(format: ChannelFormat) -> OutputDepth {
+        match format {
+            ChannelFormat::Uint8 => OutputDepth::Uint8,
+            ChannelFormat::Uint16 => OutputDepth::Uint16,
+            ChannelFormat::Float32 => OutputDepth::Float32,
+        }
+    }
+}
+
+/// Everything that can go wrong while combining a set of sources.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CombineError {
+    /// The swizzle mask was shorter than 2 characters, leaving nothing to combine.
+    SwizzleMaskTooShort(usize),
+    /// The swizzle mask referenced a source index (`0`-`3`), but no image was set for it.
+    MissingSource(usize),
+    /// The swizzle mask referenced a source index outside `0..=3`.
+    SourceOutOfBounds(u32),
+    /// A swizzle mask character wasn't a source index (`0`-`3`) or fill value (`b`, `w`, `g`).
+    InvalidSwizzleChar(char),
+    /// A select mask character wasn't a channel name (`r`, `g`, `b`, `a`).
+    InvalidSelectChar(char),
+    /// Source `index` didn't share the dimensions of the other configured sources (every source
+    /// that was set is checked, whether or not the swizzle mask reads it).
+    SizeMismatch { index: usize, width: u32, height: u32, expected_width: u32, expected_height: u32 },
+}
+
+impl std::fmt::Display for CombineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CombineError::SwizzleMaskTooShort(len) => write!(f, "swizzle mask is {} character(s) long, need at least 2", len),
+            CombineError::MissingSource(index) => write!(f, "swizzle mask needs input source '{}', but none was provided", index),
+            CombineError::SourceOutOfBounds(index) => write!(f, "swizzle mask contains source image out of bounds: {}", index),
+            CombineError::InvalidSwizzleChar(c) => write!(f, "invalid swizzle character '{}'", c),
+            CombineError::InvalidSelectChar(c) => write!(f, "invalid select mask character '{}'", c),
+            CombineError::SizeMismatch { index, width, height, expected_width, expected_height } =>
+                write!(f, "input {} is {}x{}, but expected {}x{} to match the other sources", index, width, height, expected_width, expected_height),
+        }
+    }
+}
+
+impl std::error::Error for CombineError {}
+
+/// A single channel value sample and target type, covering every source/output combination so
+/// mixed-depth inputs are normalized instead of truncated.
+trait ChannelSample: image::Primitive + Send + Sync {
+    fn from_u8(v: u8) -> Self;
+    fn from_u16(v: u16) -> Self;
+    fn from_f32(v: f32) -> Self;
+    fn black() -> Self;
+    fn white() -> Self;
+    fn gray() -> Self;
+}
+
+impl ChannelSample for u8 {
+    fn from_u8(v: u8) -> Self { v }
+    fn from_u16(v: u16) -> Self { ((v as u32 + 128) / 257) as u8 }
+    fn from_f32(v: f32) -> Self { (v.clamp(0.0, 1.0) * 255.0).round() as u8 }
+    fn black() -> Self { 0 }
+    fn white() -> Self { 255 }
+    fn gray() -> Self { 128 }
+}
+
+impl ChannelSample for u16 {
+    fn from_u8(v: u8) -> Self { v as u16 * 257 }
+    fn from_u16(v: u16) -> Self { v }
+    fn from_f32(v: f32) -> Self { (v.clamp(0.0, 1.0) * 65535.0).round() as u16 }
+    fn black() -> Self { 0 }
+    fn white() -> Self { 65535 }
+    fn gray() -> Self { 32768 }
+}
+
+impl ChannelSample for f32 {
+    fn from_u8(v: u8) -> Self { v as f32 / 255.0 }
+    fn from_u16(v: u16) -> Self { v as f32 / 65535.0 }
+    fn from_f32(v: f32) -> Self { v }
+    fn black() -> Self { 0.0 }
+    fn white() -> Self { 1.0 }
+    fn gray() -> Self { 0.5 }
+}
+
+/// Resolve a swizzle-mask fill character ('b', 'w', 'g') to the corresponding value in `T`'s domain
+fn fill_value<T: ChannelSample>(kind: char) -> T {
+    match kind {
+        'b' => T::black(),
+        'w' => T::white(),
+        _ => T::gray(),
+    }
+}
+
+/// Combine the selected source channels into a single interleaved RGBA pixel buffer, normalizing
+/// each sampled channel from its source format into the `T` output domain. Returns the raw
+/// `Vec<T>` rather than an `ImageBuffer<Rgba<T>, _>`, since `Rgba<T>: Pixel` needs `T: Enlargeable`,
+/// a bound only `image` itself can name (its `traits` module is private) — callers build the
+/// concrete `ImageBuffer` once `T` is resolved to `u8`/`u16`/`f32`.
+#[allow(clippy::too_many_arguments)]
+fn combine_channels<T: ChannelSample + 'static>(
+    width: u32,
+    height: u32,
+    fill: [T; 4],
+    swizzled_images: &[&[u8]],
+    output_channels: &[usize],
+    byte_strides: &[u8],
+    channel_strides: &[u8],
+    channel_selects: &[usize],
+    formats: &[ChannelFormat],
+    num_cpus: usize,
+    thread_job_size: usize,
+) -> Vec<T> {
+    let mut pixels: Vec<T> = Vec::with_capacity(width as usize * height as usize * 4);
+    for _ in 0..(width as usize * height as usize) {
+        pixels.push(fill[0]);
+        pixels.push(fill[1]);
+        pixels.push(fill[2]);
+        pixels.push(fill[3]);
+    }
+
+    for img_idx in 0..swizzled_images.len() {
+        let read_stride = byte_strides[img_idx] as usize;
+        let channel_stride = channel_strides[img_idx] as usize;
+        let channel_select_offset = channel_selects[img_idx];
+        let format = formats[img_idx];
+        let output_channel = output_channels[img_idx];
+        let mut source_data = swizzled_images[img_idx].chunks(thread_job_size * read_stride);
+        let mut dest_data = pixels.chunks_mut(thread_job_size * 4);
+
+        for _ in (0..source_data.len()).step_by(num_cpus) {
+            thread::scope(|s: &thread::Scope<'_, '_>| {
+                for _ in 0..num_cpus {
+                    if let Some(source_chunk) = source_data.next() {
+                        let dest_chunk = dest_data.next().unwrap();
+                        s.spawn(move || {
+                            // The last chunk of a non-multiple-of-`thread_job_size` image is shorter
+                            // than `thread_job_size` pixels; bound the loop by what's actually here.
+                            let pixel_count = dest_chunk.len() / 4;
+                            for i in 0..pixel_count {
+                                let value: T;
+                                unsafe {
+                                    value = match format {
+                                        ChannelFormat::Uint8 => T::from_u8(source_chunk[i * channel_stride + channel_select_offset]),
+                                        ChannelFormat::Uint16 => {
+                                            T::from_u16(std::mem::transmute::<&[u8], &[u16]>(source_chunk)[i * channel_stride + channel_select_offset])
+                                        },
+                                        ChannelFormat::Float32 => {
+                                            T::from_f32(std::mem::transmute::<&[u8], &[f32]>(source_chunk)[i * channel_stride + channel_select_offset])
+                                        }
+                                    }
+                                }
+                                dest_chunk[i * 4 + output_channel] = value;
+                            }
+                        });
+                    }
+                }
+            });
+        }
+    }
+
+    pixels
+}
+
+/// Builder for packing up to four source images' channels into one output image.
+///
+/// ```no_run
+/// use image_combiner::ImageCombiner;
+///
+/// let source0 = image::open("a.png").unwrap();
+/// let source1 = image::open("b.png").unwrap();
+/// let (combined, warnings) = ImageCombiner::new()
+///     .source(0, source0)
+///     .source(1, source1)
+///     .swizzle_mask("01bw")
+///     .select_mask("rrrr")
+///     .combine()
+///     .unwrap();
+/// ```
+pub struct ImageCombiner {
+    sources: [Option<DynamicImage>; 4],
+    swizzle_mask: String,
+    select_mask: String,
+    output_depth: Option<OutputDepth>,
+}
+
+impl ImageCombiner {
+    pub fn new() -> ImageCombiner {
+        ImageCombiner {
+            sources: [None, None, None, None],
+            swizzle_mask: "bbbw".to_string(),
+            select_mask: "rrrr".to_string(),
+            output_depth: None,
+        }
+    }
+
+    /// Set the source image at `index` (0-3)
+    pub fn source(mut self, index: usize, image: DynamicImage) -> Self {
+        self.sources[index] = Some(image);
+        self
+    }
+
+    /// The swizzle mask, see the crate-level docs for its syntax. Default is "bbbw"
+    pub fn swizzle_mask(mut self, mask: &str) -> Self {
+        self.swizzle_mask = mask.to_string();
+        self
+    }
+
+    /// The select mask, see the crate-level docs for its syntax. Default is "rrrr"
+    pub fn select_mask(mut self, mask: &str) -> Self {
+        self.select_mask = mask.to_string();
+        self
+    }
+
+    /// Force the output bit depth instead of inferring it from the highest depth among the selected inputs
+    pub fn output_depth(mut self, depth: OutputDepth) -> Self {
+        self.output_depth = Some(depth);
+        self
+    }
+
+    /// Run the swizzle/select masks over the configured sources and produce the combined image,
+    /// along with any warnings raised along the way (e.g. a select mask channel clamped down to
+    /// fit a source with fewer channels than the mask asks for).
+    pub fn combine(&self) -> Result<(DynamicImage, Vec<String>), CombineError> {
+        if self.swizzle_mask.len() < 2 {
+            return Err(CombineError::SwizzleMaskTooShort(self.swizzle_mask.len()));
+        }
+
+        let select_mask_bytes = self.select_mask.as_bytes();
+        let mut channel_selects: [usize; 4] = [0, 0, 0, 0];
+        for path_idx in 0..self.sources.len() {
+            if self.sources[path_idx].is_some() {
+                channel_selects[path_idx] = match select_mask_bytes[path_idx] as char {
+                    'r' => 0,
+                    'g' => 1,
+                    'b' => 2,
+                    'a' => 3,
+                    other => return Err(CombineError::InvalidSelectChar(other)),
+                };
+            }
+        }
+
+        // Break down swizzle mask into components
+        let mut fill_kind = ['b', 'b', 'b', 'w'];
+        let swizzles: Vec<Option<u32>> = self.swizzle_mask.chars().map(|f| f.to_digit(10)).collect();
+        let swizzle_mask_bytes = self.swizzle_mask.as_bytes();
+        let mut swizzled_images = Vec::<&[u8]>::new();
+        let mut output_channels = Vec::<usize>::new();
+        let mut selected_channels = Vec::<usize>::new();
+        let mut byte_strides = Vec::<u8>::new();
+        let mut channel_strides = Vec::<u8>::new();
+        let mut formats = Vec::<ChannelFormat>::new();
+        let mut warnings = Vec::<String>::new();
+        for channel in 0..swizzles.len() {
+            if let Some(swizzle) = swizzles[channel] {
+                if swizzle > 3 {
+                    return Err(CombineError::SourceOutOfBounds(swizzle));
+                }
+                let swizzle = swizzle as usize;
+                if let Some(file) = &self.sources[swizzle] {
+                    swizzled_images.push(file.as_bytes());
+                    output_channels.push(channel);
+                    byte_strides.push(file.color().bytes_per_pixel());
+                    let channel_count = file.color().channel_count();
+                    channel_strides.push(channel_count);
+                    if channel_count <= channel_selects[swizzle] as u8 {
+                        warnings.push(format!(
+                            "[WARNING] Input {} has {} channel(s) but select mask is '{}', clamping channel to {}",
+                            swizzle, channel_count, select_mask_bytes[swizzle] as char, channel_count
+                        ));
+                        channel_selects[swizzle] = (channel_count - 1) as usize;
+                    }
+                    // `channel_selects` is indexed by source index, but `combine_channels` walks
+                    // sources in push order (the same order as `swizzled_images`/`output_channels`)
+                    // - carry the resolved select alongside them instead of the source index.
+                    selected_channels.push(channel_selects[swizzle]);
+                    let format = match file.color() {
+                        image::ColorType::L8 => ChannelFormat::Uint8,
+                        image::ColorType::La8 => ChannelFormat::Uint8,
+                        image::ColorType::Rgb8 => ChannelFormat::Uint8,
+                        image::ColorType::Rgba8 => ChannelFormat::Uint8,
+
+                        image::ColorType::L16 => ChannelFormat::Uint16,
+                        image::ColorType::La16 => ChannelFormat::Uint16,
+                        image::ColorType::Rgb16 => ChannelFormat::Uint16,
+                        image::ColorType::Rgba16 => ChannelFormat::Uint16,
+
+                        image::ColorType::Rgb32F => ChannelFormat::Float32,
+                        image::ColorType::Rgba32F => ChannelFormat::Float32,
+                        _ => ChannelFormat::Uint8
+                    };
+                    formats.push(format);
+                } else {
+                    return Err(CombineError::MissingSource(swizzle));
+                }
+            } else {
+                match swizzle_mask_bytes[channel] as char {
+                    'b' => fill_kind[channel] = 'b',
+                    'w' => fill_kind[channel] = 'w',
+                    'g' => fill_kind[channel] = 'g',
+                    other => return Err(CombineError::InvalidSwizzleChar(other)),
+                }
+            }
+        }
+
+        // Resolve the output depth: explicit override, or the highest depth among selected inputs
+        let resolved_depth = self.output_depth.unwrap_or_else(|| {
+            formats.iter().fold(OutputDepth::Uint8, |acc, format| {
+                let candidate = OutputDepth::from_format(*format);
+                if candidate > acc { candidate } else { acc }
+            })
+        });
+
+        // Assert all sources have the same size
+        let mut size: Option<(u32, u32)> = None;
+        for (index, source) in self.sources.iter().enumerate() {
+            if let Some(image) = source {
+                match size {
+                    None => size = Some((image.width(), image.height())),
+                    Some((width, height)) => {
+                        if image.width() != width || image.height() != height {
+                            return Err(CombineError::SizeMismatch {
+                                index,
+                                width: image.width(),
+                                height: image.height(),
+                                expected_width: width,
+                                expected_height: height,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        let (width, height) = size.unwrap_or((0, 0));
+
+        let thread_job_size = width as usize * 32;
+        let num_cpus = num_cpus::get(); // Assume hyperthreading
+
+        // `Rgba<T>: Pixel` needs `T: Enlargeable`, a bound only `image` itself can name (its
+        // `traits` module isn't public), so each depth builds its own concrete `ImageBuffer`
+        // here rather than going through a shared generic constructor.
+        let combined = match resolved_depth {
+            OutputDepth::Uint8 => {
+                let fill = [
+                    fill_value::<u8>(fill_kind[0]), fill_value::<u8>(fill_kind[1]),
+                    fill_value::<u8>(fill_kind[2]), fill_value::<u8>(fill_kind[3])
+                ];
+                let pixels = combine_channels::<u8>(width, height, fill, &swizzled_images, &output_channels, &byte_strides, &channel_strides, &selected_channels, &formats, num_cpus, thread_job_size);
+                DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            },
+            OutputDepth::Uint16 => {
+                let fill = [
+                    fill_value::<u16>(fill_kind[0]), fill_value::<u16>(fill_kind[1]),
+                    fill_value::<u16>(fill_kind[2]), fill_value::<u16>(fill_kind[3])
+                ];
+                let pixels = combine_channels::<u16>(width, height, fill, &swizzled_images, &output_channels, &byte_strides, &channel_strides, &selected_channels, &formats, num_cpus, thread_job_size);
+                DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            },
+            OutputDepth::Float32 => {
+                let fill = [
+                    fill_value::<f32>(fill_kind[0]), fill_value::<f32>(fill_kind[1]),
+                    fill_value::<f32>(fill_kind[2]), fill_value::<f32>(fill_kind[3])
+                ];
+                let pixels = combine_channels::<f32>(width, height, fill, &swizzled_images, &output_channels, &byte_strides, &channel_strides, &selected_channels, &formats, num_cpus, thread_job_size);
+                DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            },
+        };
+
+        Ok((combined, warnings))
+    }
+}
+
+impl Default for ImageCombiner {
+    fn default() -> Self {
+        ImageCombiner::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn from_u8_widens_to_u16_full_scale() {
+        assert_eq!(u16::from_u8(0), 0);
+        assert_eq!(u16::from_u8(255), 65535);
+    }
+
+    #[test]
+    fn from_u16_narrows_to_u8_with_rounding() {
+        assert_eq!(u8::from_u16(0), 0);
+        assert_eq!(u8::from_u16(65535), 255);
+    }
+
+    #[test]
+    fn from_f32_clamps_before_scaling() {
+        assert_eq!(u8::from_f32(0.0), 0);
+        assert_eq!(u8::from_f32(1.0), 255);
+        assert_eq!(u8::from_f32(2.0), 255);
+        assert_eq!(u8::from_f32(-1.0), 0);
+    }
+
+    #[test]
+    fn fill_value_resolves_black_white_gray() {
+        assert_eq!(fill_value::<u8>('b'), 0);
+        assert_eq!(fill_value::<u8>('w'), 255);
+        assert_eq!(fill_value::<u8>('g'), 128);
+    }
+
+    #[test]
+    fn combine_rejects_short_swizzle_mask() {
+        let err = ImageCombiner::new().swizzle_mask("b").combine().unwrap_err();
+        assert_eq!(err, CombineError::SwizzleMaskTooShort(1));
+    }
+
+    #[test]
+    fn combine_rejects_missing_source() {
+        let err = ImageCombiner::new().swizzle_mask("0bbb").combine().unwrap_err();
+        assert_eq!(err, CombineError::MissingSource(0));
+    }
+
+    #[test]
+    fn combine_warns_when_select_mask_clamps_to_a_narrower_source() {
+        // source0 only has an L8 (single) channel, but the select mask asks for its alpha - the
+        // select must clamp down to the source's last channel and surface that as a warning.
+        let source0 = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(1, 1, image::Luma([42])));
+        let (_, warnings) = ImageCombiner::new()
+            .source(0, source0)
+            .swizzle_mask("0bbw")
+            .select_mask("abbb")
+            .combine()
+            .unwrap();
+        assert_eq!(warnings, vec!["[WARNING] Input 0 has 1 channel(s) but select mask is 'a', clamping channel to 1".to_string()]);
+    }
+
+    #[test]
+    fn combine_rejects_size_mismatch() {
+        let source0 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(2, 2, Rgba([1, 2, 3, 4])));
+        let source1 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([1, 2, 3, 4])));
+        let err = ImageCombiner::new()
+            .source(0, source0)
+            .source(1, source1)
+            .swizzle_mask("01bb")
+            .combine()
+            .unwrap_err();
+        assert_eq!(err, CombineError::SizeMismatch { index: 1, width: 1, height: 1, expected_width: 2, expected_height: 2 });
+    }
+
+    #[test]
+    fn combine_maps_fill_before_source_to_the_right_output_channel() {
+        // "g012" fills output channel 0 with mid-gray and reads sources 0/1/2 into channels 1/2/3 -
+        // a fill ahead of a source in the mask must not shift the sources into the wrong channel.
+        let source0 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([10, 0, 0, 0])));
+        let source1 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([20, 0, 0, 0])));
+        let source2 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([30, 0, 0, 0])));
+        let (combined, warnings) = ImageCombiner::new()
+            .source(0, source0)
+            .source(1, source1)
+            .source(2, source2)
+            .swizzle_mask("g012")
+            .select_mask("rrrr")
+            .combine()
+            .unwrap();
+        assert!(warnings.is_empty());
+        let pixel = combined.as_bytes();
+        assert_eq!(pixel, &[128, 10, 20, 30]);
+    }
+
+    #[test]
+    fn combine_reads_the_select_channel_of_its_own_source_not_its_push_position() {
+        // "10bw" reads source 1 into output channel 0 and source 0 into output channel 1 - the
+        // reverse of push order - so a select-mask lookup keyed by push position instead of source
+        // index would read each source's wrong channel.
+        let source0 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([10, 11, 12, 13])));
+        let source1 = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([20, 21, 22, 23])));
+        let (combined, warnings) = ImageCombiner::new()
+            .source(0, source0)
+            .source(1, source1)
+            .swizzle_mask("10bw")
+            .select_mask("ab")
+            .combine()
+            .unwrap();
+        assert!(warnings.is_empty());
+        let pixel = combined.as_bytes();
+        assert_eq!(pixel, &[22, 13, 0, 255]);
+    }
+
+    #[test]
+    fn combine_respects_the_last_ragged_thread_chunk() {
+        // height = 33 so the 32-row thread chunking leaves a 1-row final chunk; this must not panic
+        // and must still produce correct output for every row.
+        let source = DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 33, |_, y| Rgba([y as u8, 0, 0, 255])));
+        let (combined, warnings) = ImageCombiner::new()
+            .source(0, source)
+            .swizzle_mask("000w")
+            .select_mask("rrrr")
+            .combine()
+            .unwrap();
+        assert!(warnings.is_empty());
+        let bytes = combined.as_bytes();
+        for y in 0..33u32 {
+            let row_start = (y * 4 * 4) as usize;
+            assert_eq!(bytes[row_start], y as u8);
+        }
+    }
+}
@@ -5,14 +5,9 @@ use std::time::Instant;
 use colored::Colorize;
 
 use crossterm::{QueueableCommand, cursor};
-use image::{RgbaImage, DynamicImage, Rgba};
+use image::DynamicImage;
+use image_combiner::{ImageCombiner, OutputDepth};
 
-#[derive(Clone, Copy, Debug)]
-enum ChannelFormat {
-    Uint8,
-    Uint16,
-    Float32
-}
 struct Timer {
     time: Instant
 }
@@ -20,7 +15,7 @@ struct Timer {
 impl Timer {
 
     fn new() -> Timer {
-        return Timer { time: Instant::now() }
+        Timer { time: Instant::now() }
     }
 
     /// Start the timer
@@ -47,11 +42,11 @@ struct Printer {
 
 impl Printer {
     fn new() -> Printer {
-        return Printer {prev_str: String::new(), offset: crossterm::cursor::position().unwrap().1}
+        Printer {prev_str: String::new(), offset: crossterm::cursor::position().unwrap().1}
     }
 
     fn reserve_line(&mut self, offset: u16) {
-        self.offset = self.offset + offset;
+        self.offset += offset;
     }
 
     fn start_print(&mut self, str: String) {
@@ -63,7 +58,7 @@ impl Printer {
 
     fn finish_print(&self, res: bool) {
         let mut stdout = io::stdout();
-        stdout.queue(cursor::MoveTo(0, self.offset)).unwrap(); 
+        stdout.queue(cursor::MoveTo(0, self.offset)).unwrap();
         let padding = 96 - self.prev_str.len();
         if res {
             stdout.write_all(format!("{} {: >padding$}\n", self.prev_str, "done".bold().green()).as_bytes()).unwrap();
@@ -95,27 +90,39 @@ fn help() {
     -3 <path> Path of source image 3
     -s <mask> The swizzle mask, default is bbbw
     -m <mask> The select mask, default is rrrr
+    -d <depth> The output bit depth, one of 8, 16 or 32f. Defaults to the highest depth among the selected inputs.
+               32f cannot be written out as PNG (no float sample type) - use -d 8 or -d 16 when writing a file
     -o <path> Output path
+    -i, --info Print a report of each provided input's color type, bit depth, channel count, dimensions and
+               interlacing instead of combining. No output is written.
+    -p <index> Carry the tEXt/zTXt/iTXt metadata chunks from input <index> (0-3) through to the output
+    -t <key=value> Inject an additional text metadata chunk into the output. May be passed multiple times
+    --strip-metadata Write a clean output with no text metadata, overriding -p and -t
 
     The swizzle mask (-s) maps the value in the mask to the channel in the output image corresponding to its index.
     Allowed values are:
         [0..3] - Reads the image at index
         b, w, g - Fills with either 0 (b), 255 (w) or 128 (g)
 
-    By default, the swizzle mask is bbbw which means the output image will have [0, 0, 0, 255] in every channel. 
-    Example 1: 
+    By default, the swizzle mask is bbbw which means the output image will have [0, 0, 0, 255] in every channel.
+    Example 1:
         Mask 0123 would map [s0, s1, s2, s3] to output [r, g, b, a] by extracting the first channel in each source image.
-    Example 2: 
+    Example 2:
         Mask 01bw would map [s0, s1, 0, 255] to output [r, g, b, a].
 
     In the above examples, the s prefix corresponds to a source image.
 
     The select mask (-m) selects which channel from the source image to select. By default it's [r, r, r, r]
+
+    The depth mask (-d) controls the precision of the output image. u16 and f32 sources are normalized into the
+    output domain rather than being truncated to 8 bits, e.g. a 16-bit value v becomes (v as f32 / 65535.0) for
+    a 32-bit float output. The b, w and g fills scale with the output depth: 0, 65535 and 32768 at 16 bits,
+    and 0.0, 1.0 and 0.5 at 32f.
 ");
 }
 
 /// Open images for reading
-fn open_source_images(paths: &Vec<Option<&std::path::Path>>) -> [Option<DynamicImage>; 4] {
+fn open_source_images(paths: &[Option<&std::path::Path>]) -> [Option<DynamicImage>; 4] {
 
     // Setup array of image results
     let mut rets = [None, None, None, None];
@@ -123,47 +130,132 @@ fn open_source_images(paths: &Vec<Option<&std::path::Path>>) -> [Option<DynamicI
     thread::scope(|s| {
 
         // Each file will have it's own line in the output
-        let mut cursor_offset: u16 = 0;
-        for path_idx in 0..paths.len() {
-            if let Some(path) = paths[path_idx] {
-
-                // Create a new printer for the thread
-                let mut local_printer = Printer::new();
-
-                // Assign the printer a line
-                local_printer.reserve_line(cursor_offset);
-                cursor_offset += 1;
-                local_printer.start_print(format!("Reading {}", path.to_str().unwrap().bold()));
-                let ret = rets_iter.next().unwrap();
-                s.spawn(move || {
-                    let path_str = path.to_str().unwrap();
-                    let image_reader = image::io::Reader::open(path_str);
-                    if let Ok(image) = image_reader {
-                        if let Ok(image_raw) = image.decode() {
-
-                            // Success, terminate thread
-                            local_printer.finish_print(true);
-                            ret.replace(image_raw);
-                            return;
-                        }
+        // Pair each path with the result slot at its own input index, so a missing `-0` doesn't
+        // shift later inputs into the wrong slot
+        let provided = paths.iter().zip(rets_iter.by_ref()).filter_map(|(path, ret)| path.map(|p| (p, ret)));
+        for (cursor_offset, (path, ret)) in (0_u16..).zip(provided) {
+
+            // Create a new printer for the thread
+            let mut local_printer = Printer::new();
+
+            // Assign the printer a line
+            local_printer.reserve_line(cursor_offset);
+            local_printer.start_print(format!("Reading {}", path.to_str().unwrap().bold()));
+            s.spawn(move || {
+                let path_str = path.to_str().unwrap();
+                let image_reader = image::io::Reader::open(path_str);
+                if let Ok(image) = image_reader {
+                    // `image`'s PNG decoder already expands indexed/palette color (and tRNS
+                    // alpha) to true RGB(A) on decode, so the source is already uniform here.
+                    if let Ok(image_raw) = image.decode() {
+
+                        // Success, terminate thread
+                        local_printer.finish_print(true);
+                        ret.replace(image_raw);
+                        return;
                     }
-        
-                    local_printer.finish_print(false);
-                });
-            }
-        }        
+                }
+
+                local_printer.finish_print(false);
+            });
+        }
     });
 
-    return rets;
+    rets
+}
+
+/// Describe a decoded color type the way a PNG inspector would, e.g. "RGBA" or "grayscale+alpha"
+fn describe_color_type(color: image::ColorType) -> &'static str {
+    match color {
+        image::ColorType::L8 | image::ColorType::L16 => "grayscale",
+        image::ColorType::La8 | image::ColorType::La16 => "grayscale+alpha",
+        image::ColorType::Rgb8 | image::ColorType::Rgb16 | image::ColorType::Rgb32F => "RGB",
+        image::ColorType::Rgba8 | image::ColorType::Rgba16 | image::ColorType::Rgba32F => "RGBA",
+        _ => "indexed/palette",
+    }
+}
+
+/// Describe a PNG's color type as stored in the file itself, e.g. "indexed/palette" - unlike
+/// `describe_color_type`, this reflects the source before `image` expands indexed color on decode
+fn describe_png_color_type(color: png::ColorType) -> &'static str {
+    match color {
+        png::ColorType::Grayscale => "grayscale",
+        png::ColorType::GrayscaleAlpha => "grayscale+alpha",
+        png::ColorType::Rgb => "RGB",
+        png::ColorType::Rgba => "RGBA",
+        png::ColorType::Indexed => "indexed/palette",
+    }
+}
+
+/// The bit depth per channel for a decoded color type
+fn bit_depth_of(color: image::ColorType) -> &'static str {
+    match color {
+        image::ColorType::L8 | image::ColorType::La8 | image::ColorType::Rgb8 | image::ColorType::Rgba8 => "8",
+        image::ColorType::L16 | image::ColorType::La16 | image::ColorType::Rgb16 | image::ColorType::Rgba16 => "16",
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F => "32 (float)",
+        _ => "8",
+    }
+}
+
+/// Peek at the raw PNG header to report the interlace pass, since `image` discards it after decode
+fn png_interlaced(path: &Path) -> Option<bool> {
+    let file = std::fs::File::open(path).ok()?;
+    let decoder = png::Decoder::new(file);
+    let reader = decoder.read_info().ok()?;
+    Some(reader.info().interlaced)
+}
+
+/// Peek at the raw PNG header for the color type as stored in the file, before `image`'s decoder
+/// expands indexed/palette color to RGB(A)
+fn png_color_type(path: &Path) -> Option<png::ColorType> {
+    let file = std::fs::File::open(path).ok()?;
+    let decoder = png::Decoder::new(file);
+    let reader = decoder.read_info().ok()?;
+    Some(reader.info().color_type)
+}
+
+/// Print a pngcheck-style report for every provided `-0..-3` input, without combining anything
+fn print_info(printer: &mut Printer, paths: &Vec<Option<&Path>>) {
+    let images = open_source_images(paths);
+    for path_idx in 0..paths.len() {
+        if let Some(path) = paths[path_idx] {
+            if let Some(image) = &images[path_idx] {
+                let color = image.color();
+                let color_type_label = match png_color_type(path) {
+                    Some(png_color) => describe_png_color_type(png_color),
+                    None => describe_color_type(color),
+                };
+                println!("Input {} - {}", path_idx, path.to_str().unwrap().bold());
+                println!("    Color type: {}", color_type_label);
+                println!("    Bit depth:  {} bits/channel", bit_depth_of(color));
+                println!("    Channels:   {}", color.channel_count());
+                println!("    Dimensions: {}x{}", image.width(), image.height());
+                println!("    Interlaced: {}", match png_interlaced(path) {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "n/a",
+                });
+            } else {
+                printer.fail_print(format!("Could not decode {}", path.to_str().unwrap()));
+            }
+        }
+    }
 }
 
 fn main() {
-    let args : Vec<String> = env::args().collect();
+    let mut args : Vec<String> = env::args().collect();
+    let info_mode = args.iter().any(|a| a == "-i" || a == "--info");
+    let strip_metadata = args.iter().any(|a| a == "--strip-metadata");
+    args.retain(|a| a != "-i" && a != "--info" && a != "--strip-metadata");
+
     let mut printer = Printer::new();
     let mut paths : Vec<Option<&Path>> = vec![None, None, None, None];
     let mut swizzle_mask: &str = "bbbw";
     let mut select_mask: &str = "rrrr";
     let mut output_path = None;
+    let mut output_depth: Option<OutputDepth> = None;
+    let mut preserve_metadata_source: Option<usize> = None;
+    let mut injected_text: Vec<(String, String)> = Vec::new();
     if (1..args.len()).len() % 2 != 0 {
         help();
         return;
@@ -177,6 +269,38 @@ fn main() {
             "-m" => select_mask = args[arg_i + 1].as_str(),
             "-s" => swizzle_mask = args[arg_i + 1].as_str(),
             "-o" => output_path = Some(Path::new(args[arg_i + 1].as_str())),
+            "-d" => {
+                output_depth = match args[arg_i + 1].as_str() {
+                    "8" => Some(OutputDepth::Uint8),
+                    "16" => Some(OutputDepth::Uint16),
+                    "32f" => Some(OutputDepth::Float32),
+                    other => {
+                        printer.fail_print(format!("Invalid output depth '{}', expected 8, 16 or 32f", other));
+                        help();
+                        return;
+                    }
+                };
+            },
+            "-p" => {
+                preserve_metadata_source = match args[arg_i + 1].parse::<usize>() {
+                    Ok(idx) if idx <= 3 => Some(idx),
+                    _ => {
+                        printer.fail_print(format!("Invalid metadata source index '{}', expected 0-3", args[arg_i + 1]));
+                        help();
+                        return;
+                    }
+                };
+            },
+            "-t" => {
+                match args[arg_i + 1].split_once('=') {
+                    Some((key, value)) => injected_text.push((key.to_string(), value.to_string())),
+                    None => {
+                        printer.fail_print(format!("Invalid text metadata entry '{}', expected key=value", args[arg_i + 1]));
+                        help();
+                        return;
+                    }
+                }
+            },
             _ => {
                 help();
                 return;
@@ -184,6 +308,11 @@ fn main() {
         }
     }
 
+    if info_mode {
+        print_info(&mut printer, &paths);
+        return;
+    }
+
     if swizzle_mask.len() < 2 {
         printer.fail_print("Swizzle mask is less than 2, nothing to do here...".to_string());
         return;
@@ -196,180 +325,156 @@ fn main() {
             let mut timer = Timer::new();
 
             // Create entire file path to file
-            if let Err(_) = std::fs::create_dir_all(parent) {
+            if std::fs::create_dir_all(parent).is_err() {
                 printer.fail_print(format!("Invalid path {}", parent.to_str().unwrap()));
                 return;
             }
 
             // Open files
-            let mut channel_selects: Vec<usize> = vec![0, 0, 0, 0];
-            let select_mask_bytes = select_mask.as_bytes();
-
-            let images = open_source_images(&paths);
-            for path_idx in 0..paths.len() {
-                if let Some(path) = paths[path_idx] {
-
-                    match select_mask_bytes[path_idx] as char {
-                        'r' => channel_selects[path_idx] = 0,
-                        'g' => channel_selects[path_idx] = 1,
-                        'b' => channel_selects[path_idx] = 2,
-                        'a' => channel_selects[path_idx] = 3,
-                        other => {
-                            printer.fail_print(format!("Invalid select mask {} for input {}", other as char, path_idx));
-                            help();
-                            return;
-                        }
-                    }
-                }
-            }
+            let mut images = open_source_images(&paths);
 
-            // Break down swizzle mask into components
-            let mut fill = Rgba([0, 0, 0, 255]);
-            let swizzles: Vec<Option<u32>> = swizzle_mask.chars().map(|f| f.to_digit(10)).collect();
-            let mut swizzled_images = Vec::<&[u8]>::new();
-            let mut byte_strides = Vec::<u8>::new();
-            let mut red_channel_strides = Vec::<u8>::new();
-            let mut formats = Vec::<ChannelFormat>::new();
-            for channel in 0..swizzles.len() {
-                if let Some(swizzle) = swizzles[channel] {
-                    if swizzle > 3 {
-                        printer.fail_print(format!("Swizzle mask contains source image out of bounds {}", swizzle));
-                        help();
-                        return;
-                    }
-                    if let Some(file) = &images[swizzle as usize] {
-                        swizzled_images.push(file.as_bytes());
-                        byte_strides.push(file.color().bytes_per_pixel());
-                        let channel_count = file.color().channel_count();
-                        red_channel_strides.push(channel_count);
-                        if channel_count <= channel_selects[swizzle as usize] as u8 {
-                            printer.warn_print(format!("[WARNING] Input {} has {} channel(s) but select mask is '{}', clamping channel to {}", swizzle, channel_count, select_mask_bytes[swizzle as usize] as char, channel_count));
-                            channel_selects[swizzle as usize] = (channel_count - 1) as usize;
-                        }
-                        let format = match file.color() {
-                            image::ColorType::L8 => ChannelFormat::Uint8,
-                            image::ColorType::La8 => ChannelFormat::Uint8,
-                            image::ColorType::Rgb8 => ChannelFormat::Uint8,
-                            image::ColorType::Rgba8 => ChannelFormat::Uint8,
-    
-                            image::ColorType::L16 => ChannelFormat::Uint16,
-                            image::ColorType::La16 => ChannelFormat::Uint16,
-                            image::ColorType::Rgb16 => ChannelFormat::Uint16,
-                            image::ColorType::Rgba16 => ChannelFormat::Uint16,
-    
-                            image::ColorType::Rgb32F => ChannelFormat::Float32,
-                            image::ColorType::Rgba32F => ChannelFormat::Float32,
-                            _ => ChannelFormat::Uint8
-                        };
-                        formats.push(format);
-                    } else {
-                        printer.fail_print(format!("Swizzle mask needs input source '{}', but none provided", channel - 1));
-                        help();
-                        return;
-                    }
-                } else {
-                    // If swizzle isn't a number, check if it uses any fill value
-                    let swizzle_mask_bytes = swizzle_mask.as_bytes();
-                    match swizzle_mask_bytes[channel] as char {
-                        'b' => fill.0[channel] = 0,
-                        'w' => fill.0[channel] = 255,
-                        'g' => fill.0[channel] = 128,
-                        _ => {
-                            printer.fail_print(format!("Invalid swizzle character '{}'", swizzle_mask_bytes[channel] as char));
-                            help();
-                            return;
-                        }
-                    }
-                }
+            let mut combiner = ImageCombiner::new()
+                .swizzle_mask(swizzle_mask)
+                .select_mask(select_mask);
+            if let Some(depth) = output_depth {
+                combiner = combiner.output_depth(depth);
             }
-
-            // Assert all images have the same size
-            let mut width = 0xFFFFFFFF;
-            let mut height = 0xFFFFFFFF;
-            let mut image_size_mismatch = false;
-
-            // Get dimensions of images
-            for img_opt in &images {
-                if let Some(img) = img_opt {
-                    if width == 0xFFFFFFFF || height == 0xFFFFFFFF {
-                        width = img.width();
-                        height = img.height();
-                    } else {
-                        if width != img.width() || height != img.height() {
-                            image_size_mismatch = true;
-                            break;
-                        }
-                    }                    
+            for (idx, slot) in images.iter_mut().enumerate() {
+                if let Some(image) = slot.take() {
+                    combiner = combiner.source(idx, image);
                 }
             }
 
-            // If any size mismatches, throw error
-            if image_size_mismatch {
-                printer.fail_print("All input images must share the same size:".to_string());
-                for img_idx in 0..images.len() {
-                    if let Some(img) = &images[img_idx] {
-                        printer.fail_print(format!("{} (Input {}) - width: {}, height: {}", paths[img_idx].unwrap().to_str().unwrap(), img_idx, img.width(), img.height()));
-                    }
+            printer.start_print("Combining image".to_string());
+            let (combined, warnings) = match combiner.combine() {
+                Ok(combined) => combined,
+                Err(err) => {
+                    printer.finish_print(false);
+                    printer.fail_print(format!("{}", err));
+                    help();
+                    return;
                 }
-                help();
-                return;
+            };
+            printer.finish_print(true);
+            for warning in warnings {
+                printer.warn_print(warning);
             }
 
-            let thread_job_size = width as usize * 32;
-            let num_cpus = num_cpus::get(); // Assume hyperthreading
-
-            // Create image
-            printer.start_print(format!("Combining image {}", format!("{}x{}", width, height).bold()));
-            let mut rgba: RgbaImage = RgbaImage::from_pixel(width, height, fill);
-
-            for img_idx in 0..swizzled_images.len() {
-                let read_stride = byte_strides[img_idx] as usize;
-                let red_channel_stride = red_channel_strides[img_idx] as usize;
-                let channel_select_offset = channel_selects[img_idx] as usize;
-                let format = formats[img_idx];
-                let mut source_data = swizzled_images[img_idx].chunks(thread_job_size * read_stride);
-                let mut dest_data = rgba.chunks_mut(thread_job_size * 4);
-
-                for _ in (0..source_data.len()).step_by(num_cpus) {
-                    thread::scope(|s: &thread::Scope<'_, '_>| {
-                        for _ in 0..num_cpus {
-                            if let Some(source_chunk) = source_data.next() {
-                                let dest_chunk = dest_data.next().unwrap();
-                                s.spawn(|| {
-                                    for i in 0..thread_job_size {
-                                        let value : u8;
-                                        unsafe {
-                                            value = match format {
-                                                ChannelFormat::Uint8 => source_chunk[i * red_channel_stride + channel_select_offset],
-                                                ChannelFormat::Uint16 => {
-                                                    std::mem::transmute::<&[u8], &[u16]>(source_chunk)[i * red_channel_stride + channel_select_offset] as u8
-                                                },
-                                                ChannelFormat::Float32 => {
-                                                    std::mem::transmute::<&[u8], &[f32]>(source_chunk)[i * red_channel_stride + channel_select_offset] as u8
-                                                }
-                                            }
-                                        }
-                                        dest_chunk[i * 4 + img_idx] = value;
-                                    }
-                                });
-                            }
-                        }
-                    });
+            // Gather text metadata to carry through, unless the user asked for a clean output
+            let mut preserved_text: Vec<TextEntry> = Vec::new();
+            if !strip_metadata {
+                if let Some(src_idx) = preserve_metadata_source {
+                    if let Some(src_path) = paths[src_idx] {
+                        preserved_text = read_png_text_chunks(src_path);
+                    }
                 }
             }
-            printer.finish_print(true);
+            let final_injected_text = if strip_metadata { Vec::new() } else { injected_text };
 
             // Finally save file
             printer.start_print(format!("Writing out {}", path.to_str().unwrap().bold()));
             io::stdout().flush().unwrap();
-            if let Ok(_) = rgba.save_with_format(path, image::ImageFormat::Png) {
-                printer.finish_print(true);
-            } else {
-                printer.finish_print(false);
+            match write_png(path, &combined, &preserved_text, &final_injected_text) {
+                Ok(()) => printer.finish_print(true),
+                Err(err) => {
+                    printer.finish_print(false);
+                    printer.fail_print(format!("{}", err));
+                }
             }
 
             timer.elapsed();
-        }        
+        }
+    }
+
+}
+
+/// A PNG text metadata entry, tagged by the chunk type it was read from so it can be written back
+/// out as the same type instead of being flattened to one (iTXt is UTF-8 and tEXt/zTXt are Latin-1,
+/// so collapsing them loses fidelity and can mangle or reject non-Latin-1 content).
+#[derive(Clone, Debug)]
+enum TextEntry {
+    /// tEXt - uncompressed Latin-1
+    Text(String, String),
+    /// zTXt - compressed Latin-1
+    Compressed(String, String),
+    /// iTXt - UTF-8, optionally compressed
+    International(String, String),
+}
+
+/// Read back the tEXt/zTXt/iTXt chunks of a PNG source, so they can be carried through to the output
+fn read_png_text_chunks(path: &Path) -> Vec<TextEntry> {
+    let mut entries = Vec::new();
+    if let Ok(file) = std::fs::File::open(path) {
+        let decoder = png::Decoder::new(file);
+        if let Ok(mut reader) = decoder.read_info() {
+            let mut buf = vec![0u8; reader.output_buffer_size()];
+            let _ = reader.next_frame(&mut buf);
+            let info = reader.info();
+            for text in &info.uncompressed_latin1_text {
+                entries.push(TextEntry::Text(text.keyword.clone(), text.text.clone()));
+            }
+            for text in &info.compressed_latin1_text {
+                if let Ok(value) = text.get_text() {
+                    entries.push(TextEntry::Compressed(text.keyword.clone(), value));
+                }
+            }
+            for text in &info.utf8_text {
+                if let Ok(value) = text.get_text() {
+                    entries.push(TextEntry::International(text.keyword.clone(), value));
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// Write the combined image out as a PNG through `png::Encoder` rather than `save_with_format`,
+/// since the `image` crate's high-level writer has no way to emit arbitrary text chunks.
+/// `preserved_text` is written back out as its original chunk type; `injected_text` (from -t) is
+/// written as plain tEXt.
+fn write_png(path: &Path, image: &DynamicImage, preserved_text: &[TextEntry], injected_text: &[(String, String)]) -> io::Result<()> {
+    let to_io_err = |e: png::EncodingError| io::Error::other(format!("failed to write PNG text chunk: {}", e));
+
+    let write_text_chunks = |encoder: &mut png::Encoder<io::BufWriter<std::fs::File>>| -> io::Result<()> {
+        for entry in preserved_text {
+            match entry {
+                TextEntry::Text(key, value) => encoder.add_text_chunk(key.clone(), value.clone()),
+                TextEntry::Compressed(key, value) => encoder.add_ztxt_chunk(key.clone(), value.clone()),
+                TextEntry::International(key, value) => encoder.add_itxt_chunk(key.clone(), value.clone()),
+            }.map_err(to_io_err)?;
+        }
+        for (key, value) in injected_text {
+            encoder.add_text_chunk(key.clone(), value.clone()).map_err(to_io_err)?;
+        }
+        Ok(())
+    };
+
+    match image {
+        DynamicImage::ImageRgba8(buf) => {
+            let writer = io::BufWriter::new(std::fs::File::create(path)?);
+            let mut encoder = png::Encoder::new(writer, buf.width(), buf.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            write_text_chunks(&mut encoder)?;
+            let mut writer = encoder.write_header().map_err(to_io_err)?;
+            writer.write_image_data(buf.as_raw()).map_err(to_io_err)
+        },
+        DynamicImage::ImageRgba16(buf) => {
+            let writer = io::BufWriter::new(std::fs::File::create(path)?);
+            let mut encoder = png::Encoder::new(writer, buf.width(), buf.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            write_text_chunks(&mut encoder)?;
+            let mut writer = encoder.write_header().map_err(to_io_err)?;
+            let mut big_endian_bytes = Vec::with_capacity(buf.as_raw().len() * 2);
+            for sample in buf.as_raw() {
+                big_endian_bytes.extend_from_slice(&sample.to_be_bytes());
+            }
+            writer.write_image_data(&big_endian_bytes).map_err(to_io_err)
+        },
+        DynamicImage::ImageRgba32F(_) => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG has no 32-bit float sample type; pass -d 8 or -d 16 for file output"))
+        },
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported output image type for PNG encoding")),
     }
-    
 }